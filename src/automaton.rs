@@ -0,0 +1,305 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// An Aho-Corasick automaton recognizing any of a set of patterns as a factor.
+///
+/// Built once from a pattern set and an alphabet, it lets `AvoidingWithPrefix` count
+/// words avoiding those patterns in time polynomial in the word length, instead of
+/// enumerating every word of the alphabet and checking each one with `contains`.
+/// Generic over the letter type `L`, the same way the trie it is built from is generic
+/// over its symbol type.
+pub(crate) struct Automaton<L: Eq + Hash + Clone> {
+    /// Trie children, `children[state][letter] == child state`.
+    children: Vec<HashMap<L, usize>>,
+    /// Whether this state, or any state reachable by following failure links, ends a
+    /// pattern (i.e. reaching this state means a pattern has been matched).
+    terminal: Vec<bool>,
+    /// Complete transition table, `delta[state][letter]`, precomputed over `alphabet`
+    /// so that walking the automaton never needs to follow failure links at query time.
+    /// Failure links themselves are only needed to build this table, so they aren't
+    /// kept around afterwards.
+    delta: Vec<HashMap<L, usize>>,
+}
+
+impl<L: Eq + Hash + Clone> Automaton<L> {
+    /// Build the automaton matching any of `patterns`, complete over `alphabet`.
+    pub(crate) fn new(patterns: &[Vec<L>], alphabet: &[L]) -> Automaton<L> {
+        let mut children = vec![HashMap::new()];
+        let mut terminal = vec![false];
+
+        for pattern in patterns {
+            let mut state = 0;
+            for letter in pattern {
+                state = match children[state].get(letter) {
+                    Some(&child) => child,
+                    None => {
+                        children.push(HashMap::new());
+                        terminal.push(false);
+                        let child = children.len() - 1;
+                        children[state].insert(letter.clone(), child);
+                        child
+                    }
+                };
+            }
+            terminal[state] = true;
+        }
+
+        let fail = Automaton::build_failure_links(&children, &mut terminal);
+        let delta = Automaton::build_transition_table(&children, &fail, alphabet);
+
+        Automaton {
+            children,
+            terminal,
+            delta,
+        }
+    }
+
+    /// Compute failure links by BFS from the root, propagating `terminal` along them so
+    /// that a state is terminal whenever it or any failure-ancestor ends a pattern.
+    fn build_failure_links(children: &[HashMap<L, usize>], terminal: &mut [bool]) -> Vec<usize> {
+        let mut fail = vec![0; children.len()];
+        let mut queue = VecDeque::new();
+        for &child in children[0].values() {
+            queue.push_back(child);
+        }
+        while let Some(state) = queue.pop_front() {
+            for (letter, &child) in children[state].iter() {
+                let mut f = fail[state];
+                while f != 0 && !children[f].contains_key(letter) {
+                    f = fail[f];
+                }
+                fail[child] = match children[f].get(letter) {
+                    Some(&s) if s != child => s,
+                    _ => 0,
+                };
+                terminal[child] = terminal[child] || terminal[fail[child]];
+                queue.push_back(child);
+            }
+        }
+        fail
+    }
+
+    fn goto(children: &[HashMap<L, usize>], fail: &[usize], state: usize, letter: &L) -> usize {
+        let mut s = state;
+        loop {
+            if let Some(&t) = children[s].get(letter) {
+                return t;
+            }
+            if s == 0 {
+                return 0;
+            }
+            s = fail[s];
+        }
+    }
+
+    fn build_transition_table(
+        children: &[HashMap<L, usize>],
+        fail: &[usize],
+        alphabet: &[L],
+    ) -> Vec<HashMap<L, usize>> {
+        let mut delta = vec![HashMap::new(); children.len()];
+        for (state, row) in delta.iter_mut().enumerate() {
+            for letter in alphabet {
+                row.insert(letter.clone(), Automaton::goto(children, fail, state, letter));
+            }
+        }
+        delta
+    }
+
+    pub(crate) fn is_terminal(&self, state: usize) -> bool {
+        self.terminal[state]
+    }
+
+    pub(crate) fn num_states(&self) -> usize {
+        self.children.len()
+    }
+
+    /// The state reached by following `letter` from `state`. `letter` must be part of
+    /// the alphabet the automaton was built over.
+    pub(crate) fn step(&self, state: usize, letter: &L) -> usize {
+        self.delta[state][letter]
+    }
+
+    /// Walk `letters` from the root, returning the resulting state, or `None` if the walk
+    /// passes through a terminal state (i.e. the letters already contain a pattern).
+    pub(crate) fn walk<'a>(&self, letters: impl Iterator<Item = &'a L>) -> Option<usize>
+    where
+        L: 'a,
+    {
+        let mut state = 0;
+        if self.terminal[state] {
+            return None;
+        }
+        for letter in letters {
+            state = self.delta[state][letter];
+            if self.terminal[state] {
+                return None;
+            }
+        }
+        Some(state)
+    }
+
+    /// The transfer matrix `matrix[s][t]`: the number of letters taking state `s` to
+    /// state `t` without landing on a terminal state.
+    fn transfer_matrix(&self) -> Vec<Vec<usize>> {
+        let n = self.num_states();
+        let mut matrix = vec![vec![0usize; n]; n];
+        for (state, row) in matrix.iter_mut().enumerate() {
+            if self.terminal[state] {
+                continue;
+            }
+            for &target in self.delta[state].values() {
+                if !self.terminal[target] {
+                    row[target] += 1;
+                }
+            }
+        }
+        matrix
+    }
+
+    /// Count words of length `steps` reachable from `state` without ever hitting a
+    /// terminal state, by propagating counts along `delta` one letter at a time.
+    pub(crate) fn count_from(&self, state: usize, steps: usize) -> usize {
+        if self.terminal[state] {
+            return 0;
+        }
+        let matrix = self.transfer_matrix();
+        let n = self.num_states();
+        let mut counts = vec![0usize; n];
+        counts[state] = 1;
+        for _ in 0..steps {
+            let mut next = vec![0usize; n];
+            for (s, &count) in counts.iter().enumerate() {
+                if count == 0 {
+                    continue;
+                }
+                for (t, &mult) in matrix[s].iter().enumerate() {
+                    next[t] += count * mult;
+                }
+            }
+            counts = next;
+        }
+        counts.into_iter().sum()
+    }
+
+    /// `table[k][state] == count_from(state, k)`, for every state and every `k` from 0
+    /// to `max_steps`. `unrank`/`rank` need `count_from` for many different states and
+    /// step counts while walking a single word, and `count_from` alone would redo the
+    /// whole length-`steps` DP from scratch for each one; this computes all of them
+    /// together in a single O(max_steps * S * |alphabet|) pass by propagating the vector
+    /// `w_k[state] = count_from(state, k)` forward via `w_{k+1} = transfer_matrix * w_k`,
+    /// computed by following each state's `delta` successors rather than a dense row
+    /// (since, for a non-terminal state, `count_from(state, k) = sum_t
+    /// (transfer_matrix^k)[state][t]`).
+    pub(crate) fn count_table(&self, max_steps: usize) -> Vec<Vec<usize>> {
+        let n = self.num_states();
+        let mut w: Vec<usize> = (0..n).map(|s| usize::from(!self.terminal[s])).collect();
+        let mut table = Vec::with_capacity(max_steps + 1);
+        table.push(w.clone());
+        for _ in 0..max_steps {
+            let mut next = vec![0usize; n];
+            for (s, slot) in next.iter_mut().enumerate() {
+                if self.terminal[s] {
+                    continue;
+                }
+                *slot = self.delta[s]
+                    .values()
+                    .filter(|&&t| !self.terminal[t])
+                    .map(|&t| w[t])
+                    .sum();
+            }
+            w = next;
+            table.push(w.clone());
+        }
+        table
+    }
+
+    /// Count words of length `steps` reachable from `state`, in O(S^3 log steps) by
+    /// exponentiating the transfer matrix instead of stepping through it `steps` times.
+    /// Preferable to `count_from` when `steps` is large relative to the state count.
+    pub(crate) fn count_from_matrix_power(&self, state: usize, steps: usize) -> usize {
+        if self.terminal[state] {
+            return 0;
+        }
+        let power = matrix_pow(&self.transfer_matrix(), steps);
+        power[state].iter().sum()
+    }
+}
+
+fn matrix_mul(a: &[Vec<usize>], b: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let n = a.len();
+    let mut result = vec![vec![0usize; n]; n];
+    for i in 0..n {
+        for k in 0..n {
+            if a[i][k] == 0 {
+                continue;
+            }
+            for j in 0..n {
+                result[i][j] += a[i][k] * b[k][j];
+            }
+        }
+    }
+    result
+}
+
+fn matrix_identity(n: usize) -> Vec<Vec<usize>> {
+    let mut matrix = vec![vec![0usize; n]; n];
+    for (i, row) in matrix.iter_mut().enumerate() {
+        row[i] = 1;
+    }
+    matrix
+}
+
+fn matrix_pow(matrix: &[Vec<usize>], mut exponent: usize) -> Vec<Vec<usize>> {
+    let n = matrix.len();
+    let mut result = matrix_identity(n);
+    let mut base = matrix.to_vec();
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = matrix_mul(&result, &base);
+        }
+        base = matrix_mul(&base, &base);
+        exponent >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Automaton;
+
+    fn letters(word: &str) -> Vec<char> {
+        word.chars().collect()
+    }
+
+    #[test]
+    fn walk_stops_at_a_matched_pattern() {
+        let automaton = Automaton::new(&[letters("aab")], &letters("ab"));
+        assert!(automaton.walk(letters("aba").iter()).is_some());
+        assert!(automaton.walk(letters("aab").iter()).is_none());
+        assert!(automaton.walk(letters("xaab").iter().skip(1)).is_none());
+    }
+
+    #[test]
+    fn count_from_matches_count_from_matrix_power() {
+        let automaton = Automaton::new(&[letters("aa"), letters("bb")], &letters("ab"));
+        for steps in 0..8 {
+            assert_eq!(
+                automaton.count_from(0, steps),
+                automaton.count_from_matrix_power(0, steps)
+            );
+        }
+    }
+
+    #[test]
+    fn count_table_matches_count_from_for_every_state_and_step() {
+        let automaton = Automaton::new(&[letters("abc")], &letters("abc"));
+        let max_steps = 6;
+        let table = automaton.count_table(max_steps);
+        for state in 0..automaton.num_states() {
+            for steps in 0..=max_steps {
+                assert_eq!(table[steps][state], automaton.count_from(state, steps));
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::cmp;
+use std::hash::Hash;
+
+use crate::automaton::Automaton;
+use crate::word::WordOfSizeIterator;
+
+/// A class of words over an alphabet of letters `L`, starting with a fixed prefix and
+/// avoiding a set of forbidden factors (patterns). Generic over `L` the way a
+/// generalized trie is generic over its symbol type, so the avoidance machinery works
+/// over integer alphabets, tuple letters, or any other `L` and not just `char`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AvoidingWithPrefix<L: Eq + Hash + Clone + Ord> {
+    prefix: Vec<L>,
+    patterns: Vec<Vec<L>>,
+    alphabet: Vec<L>,
+    is_just_prefix: bool,
+}
+
+impl<L: Eq + Hash + Clone + Ord> AvoidingWithPrefix<L> {
+    pub fn new(
+        prefix: Vec<L>,
+        patterns: Vec<Vec<L>>,
+        alphabet: Vec<L>,
+        is_just_prefix: bool,
+    ) -> AvoidingWithPrefix<L> {
+        AvoidingWithPrefix {
+            prefix,
+            patterns,
+            alphabet,
+            is_just_prefix,
+        }
+    }
+
+    /// Create a new AvoidingWithPrefix with the same patterns and alphabet
+    pub fn with_same_base(&self, prefix: Vec<L>, is_just_prefix: bool) -> AvoidingWithPrefix<L> {
+        AvoidingWithPrefix {
+            prefix,
+            alphabet: self.alphabet.clone(),
+            patterns: self.patterns.clone(),
+            is_just_prefix,
+        }
+    }
+
+    pub fn prefix(&self) -> &[L] {
+        &self.prefix
+    }
+
+    pub fn patterns(&self) -> &[Vec<L>] {
+        &self.patterns
+    }
+
+    pub fn alphabet(&self) -> &[L] {
+        &self.alphabet
+    }
+
+    pub fn is_just_prefix(&self) -> bool {
+        self.is_just_prefix
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns
+            .iter()
+            .any(|patt| contains_factor(&self.prefix, patt))
+    }
+
+    pub fn is_atom(&self) -> bool {
+        self.is_just_prefix
+    }
+
+    pub fn removable_prefix_length(&self) -> usize {
+        let m = self.patterns.iter().map(|p| p.len()).max().unwrap_or(1);
+        let start = self.prefix.len().saturating_sub(m);
+        let mut safe = start;
+        for i in start..self.prefix.len() {
+            let end = &self.prefix[i..];
+            if self
+                .patterns
+                .iter()
+                .any(|patt| end == &patt[..cmp::min(end.len(), patt.len())])
+            {
+                break;
+            }
+            safe = i + 1;
+        }
+        safe
+    }
+
+    pub fn expand_one_letter(&self) -> Vec<AvoidingWithPrefix<L>> {
+        let mut res = Vec::with_capacity(self.alphabet.len() + 1);
+        res.push(self.with_same_base(self.prefix.clone(), true));
+        for letter in &self.alphabet {
+            let mut prefix = self.prefix.clone();
+            prefix.push(letter.clone());
+            res.push(self.with_same_base(prefix, false));
+        }
+        res
+    }
+
+    pub fn remove_front_of_prefix(&self) -> Option<Vec<AvoidingWithPrefix<L>>> {
+        if self.is_just_prefix {
+            return None;
+        }
+        match self.removable_prefix_length() {
+            0 => None,
+            safe => {
+                let start_prefix = self.prefix[..safe].to_vec();
+                let end_prefix = self.prefix[safe..].to_vec();
+                Some(vec![
+                    self.with_same_base(start_prefix, true),
+                    self.with_same_base(end_prefix, false),
+                ])
+            }
+        }
+    }
+
+    pub fn minimum_size_of_object(&self) -> usize {
+        self.prefix.len()
+    }
+
+    pub fn contains(&self, word: &[L]) -> bool {
+        if word.len() < self.prefix.len() {
+            return false;
+        }
+        if word[..self.prefix.len()] != self.prefix[..] {
+            return false;
+        }
+        if word.iter().any(|letter| !self.alphabet.contains(letter)) {
+            return false;
+        }
+        if self.patterns.iter().any(|patt| contains_factor(word, patt)) {
+            return false;
+        }
+        true
+    }
+
+    pub fn objects_of_size(&self, n: usize) -> impl Iterator<Item = Vec<L>> + '_ {
+        let alphabet = self.alphabet.clone();
+        let prefix = self.prefix.clone();
+        WordOfSizeIterator::new(alphabet, n, prefix).filter(move |w| self.contains(w))
+    }
+
+    /// Build the Aho-Corasick automaton recognizing `self.patterns`, complete over
+    /// `self.alphabet`. Used to back `count_objects_of_size` in polynomial time instead
+    /// of enumerating every word of size `n`.
+    fn automaton(&self) -> Automaton<L> {
+        Automaton::new(&self.patterns, &self.alphabet)
+    }
+
+    pub fn count_objects_of_size(&self, n: usize) -> usize {
+        if n < self.prefix.len() {
+            return 0;
+        }
+        let automaton = self.automaton();
+        match automaton.walk(self.prefix.iter()) {
+            None => 0,
+            Some(state) => automaton.count_from(state, n - self.prefix.len()),
+        }
+    }
+
+    /// Same as `count_objects_of_size`, but exponentiates the automaton's transfer
+    /// matrix instead of stepping through it one letter at a time. Worth using over
+    /// `count_objects_of_size` once `n` is large relative to the number of automaton
+    /// states, since this runs in O(S^3 log n) instead of O(n * S^2).
+    pub fn count_objects_of_size_via_matrix_power(&self, n: usize) -> usize {
+        if n < self.prefix.len() {
+            return 0;
+        }
+        let automaton = self.automaton();
+        match automaton.walk(self.prefix.iter()) {
+            None => 0,
+            Some(state) => automaton.count_from_matrix_power(state, n - self.prefix.len()),
+        }
+    }
+
+    /// Which alphabet letters can still extend the current prefix without immediately
+    /// completing a forbidden pattern, and whether the prefix is already blocked (i.e.
+    /// `is_empty()`). Lets callers of `expand_one_letter` skip children that
+    /// `is_empty()` would otherwise have to discard one at a time.
+    pub fn completion_mask(&self) -> (Vec<L>, bool) {
+        let automaton = self.automaton();
+        match automaton.walk(self.prefix.iter()) {
+            None => (Vec::new(), true),
+            Some(state) => {
+                let viable = self
+                    .alphabet
+                    .iter()
+                    .filter(|letter| !automaton.is_terminal(automaton.step(state, letter)))
+                    .cloned()
+                    .collect();
+                (viable, false)
+            }
+        }
+    }
+
+    /// The `index`-th word of size `n` avoiding `self.patterns` and starting with
+    /// `self.prefix`, in the same order `objects_of_size` enumerates them (alphabet
+    /// letters in the order given by `self.alphabet`). `None` if `index` is out of
+    /// range. Inverse of `rank`. Runs in O(n * |alphabet| * S) by precomputing a table
+    /// of suffix counts once, rather than recomputing `count_from` from scratch for
+    /// every position and letter.
+    pub fn unrank(&self, n: usize, index: usize) -> Option<Vec<L>> {
+        if n < self.prefix.len() {
+            return None;
+        }
+        let automaton = self.automaton();
+        let start = automaton.walk(self.prefix.iter())?;
+        let remaining = n - self.prefix.len();
+        let table = automaton.count_table(remaining);
+        let mut index = index;
+        if index >= table[remaining][start] {
+            return None;
+        }
+
+        let mut state = start;
+        let mut word = self.prefix.clone();
+        for steps_left in (0..remaining).rev() {
+            for letter in &self.alphabet {
+                let next_state = automaton.step(state, letter);
+                if automaton.is_terminal(next_state) {
+                    continue;
+                }
+                let count = table[steps_left][next_state];
+                if index < count {
+                    word.push(letter.clone());
+                    state = next_state;
+                    break;
+                }
+                index -= count;
+            }
+        }
+        Some(word)
+    }
+
+    /// The position of `word` in the same enumeration order `objects_of_size` and
+    /// `unrank` use, or `None` if `word` is not a member of this class. Inverse of
+    /// `unrank`. Runs in O(n * |alphabet| * S), for the same reason `unrank` does.
+    pub fn rank(&self, word: &[L]) -> Option<usize> {
+        if !self.contains(word) {
+            return None;
+        }
+        let automaton = self.automaton();
+        let mut state = automaton.walk(self.prefix.iter())?;
+        let suffix = &word[self.prefix.len()..];
+        let table = automaton.count_table(suffix.len());
+        let mut rank = 0;
+        for (i, letter) in suffix.iter().enumerate() {
+            let steps_left = suffix.len() - i - 1;
+            for candidate in &self.alphabet {
+                if candidate == letter {
+                    break;
+                }
+                let next_state = automaton.step(state, candidate);
+                if !automaton.is_terminal(next_state) {
+                    rank += table[steps_left][next_state];
+                }
+            }
+            state = automaton.step(state, letter);
+        }
+        Some(rank)
+    }
+}
+
+fn contains_factor<L: PartialEq>(haystack: &[L], needle: &[L]) -> bool {
+    if needle.is_empty() {
+        return true;
+    }
+    if needle.len() > haystack.len() {
+        return false;
+    }
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AvoidingWithPrefix;
+
+    fn avoiding(patterns: &[&str], alphabet: &str) -> AvoidingWithPrefix<char> {
+        AvoidingWithPrefix::new(
+            vec![],
+            patterns.iter().map(|p| p.chars().collect()).collect(),
+            alphabet.chars().collect(),
+            false,
+        )
+    }
+
+    #[test]
+    fn count_objects_of_size_matches_brute_force_enumeration() {
+        for (patterns, alphabet) in [
+            (vec!["aaab"], "ab"),
+            (vec!["aa", "bb"], "ab"),
+            (vec!["aba"], "ab"),
+            (vec!["abc"], "abc"),
+        ] {
+            let c = avoiding(&patterns, alphabet);
+            for n in 0..8 {
+                let brute = c.objects_of_size(n).count();
+                assert_eq!(c.count_objects_of_size(n), brute, "n={n}, patterns={patterns:?}");
+                assert_eq!(
+                    c.count_objects_of_size_via_matrix_power(n),
+                    brute,
+                    "n={n}, patterns={patterns:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rank_unrank_are_inverse_and_match_enumeration_order() {
+        let c = avoiding(&["aab"], "abc");
+        for n in 0..7 {
+            let words: Vec<Vec<char>> = c.objects_of_size(n).collect();
+            for (i, word) in words.iter().enumerate() {
+                assert_eq!(c.unrank(n, i).as_ref(), Some(word));
+                assert_eq!(c.rank(word), Some(i));
+            }
+            assert_eq!(c.unrank(n, words.len()), None);
+        }
+    }
+
+    #[test]
+    fn rank_rejects_word_outside_the_alphabet() {
+        let c = avoiding(&["aa"], "ab");
+        assert_eq!(c.rank(&['a', 'b', 'c']), None);
+    }
+
+    #[test]
+    fn completion_mask_matches_is_empty_on_each_extension() {
+        let c = avoiding(&["aa"], "ab");
+        let (viable, blocked) = c.completion_mask();
+        assert!(!blocked);
+        for letter in ['a', 'b'] {
+            let mut prefix = c.prefix().to_vec();
+            prefix.push(letter);
+            let extended = c.with_same_base(prefix, false);
+            assert_eq!(viable.contains(&letter), !extended.is_empty());
+        }
+    }
+}
@@ -1,7 +1,12 @@
+mod automaton;
+mod avoiding;
+mod rational;
+mod specification;
+mod word;
+
 use pyo3::basic::CompareOp;
 use pyo3::exceptions::{PyIndexError, PyNotImplementedError};
 use pyo3::prelude::*;
-use std::cmp;
 
 use std::collections::hash_map::DefaultHasher;
 
@@ -10,7 +15,7 @@ use std::hash::{Hash, Hasher};
 
 #[pyclass(sequence)]
 struct Word {
-    letters: Vec<char>,
+    inner: word::Word<char>,
 }
 
 #[pymethods]
@@ -21,52 +26,57 @@ impl Word {
             None => vec![],
             Some(w) => w.chars().collect(),
         };
-        Word { letters }
+        Word {
+            inner: word::Word::new(letters),
+        }
     }
 
     fn __iter__(slf: PyRef<'_, Self>) -> PyResult<Py<WordIterator>> {
         let iter = WordIterator {
-            inner: slf.letters.clone().into_iter(),
+            inner: slf.inner.letters.clone().into_iter(),
         };
         Py::new(slf.py(), iter)
     }
 
     fn __str__(&self) -> String {
-        self.letters.iter().cloned().collect::<String>()
+        self.inner.letters.iter().cloned().collect::<String>()
     }
 
     fn __len__(&self) -> usize {
-        self.letters.len()
+        self.inner.len()
     }
 
     fn __add__(slf: PyRef<'_, Self>, object: String) -> Word {
         let letters = slf
+            .inner
             .letters
             .clone()
             .into_iter()
             .chain(object.chars())
             .collect();
-        Word { letters }
+        Word {
+            inner: word::Word::new(letters),
+        }
     }
 
     fn __richcmp__(&self, other: &Self, op: CompareOp) -> bool {
-        op.matches(self.letters.cmp(&other.letters))
+        op.matches(self.inner.letters.cmp(&other.inner.letters))
     }
 
     fn __hash__(&self) -> u64 {
         let mut hasher = DefaultHasher::new();
-        self.letters.hash(&mut hasher);
+        self.inner.letters.hash(&mut hasher);
         hasher.finish()
     }
 
     fn __getitem__(&self, index: isize) -> PyResult<char> {
         let mut corrected_index = index;
         if index < 0 {
-            corrected_index = index + isize::try_from(self.letters.len()).expect("Index to big");
+            corrected_index =
+                index + isize::try_from(self.inner.letters.len()).expect("Index to big");
         }
-        println!("{corrected_index} {:?}", self.letters);
         let corrected_index: usize = usize::try_from(corrected_index).expect("Invalid index");
-        match self.letters.get(corrected_index) {
+        match self.inner.letters.get(corrected_index) {
             None => Err(PyIndexError::new_err("Index out of range")),
             Some(c) => Ok(*c),
         }
@@ -75,9 +85,7 @@ impl Word {
 
 impl Word {
     fn contains(&self, pattern: &Self) -> bool {
-        let word_str: String = self.letters.iter().collect();
-        let pattern_str: String = pattern.letters.iter().collect();
-        word_str.contains(&pattern_str)
+        self.inner.contains(&pattern.inner)
     }
 }
 
@@ -100,14 +108,7 @@ impl WordIterator {
 #[pyclass]
 #[derive(PartialEq, Eq, Hash)]
 pub struct AvoidingWithPrefix {
-    // #[pyo3(get)]
-    prefix: String,
-    #[pyo3(get)]
-    patterns: Vec<String>,
-    #[pyo3(get)]
-    alphabet: Vec<char>,
-    #[pyo3(get, name = "just_prefix")]
-    is_just_prefix: bool,
+    inner: avoiding::AvoidingWithPrefix<char>,
 }
 
 #[pymethods]
@@ -123,65 +124,56 @@ impl AvoidingWithPrefix {
         AvoidingWithPrefix::new(prefix, patterns, alphabet, just_prefix)
     }
 
+    #[getter(patterns)]
+    fn get_patterns(&self) -> Vec<String> {
+        self.inner
+            .patterns()
+            .iter()
+            .map(|patt| patt.iter().collect())
+            .collect()
+    }
+
+    #[getter(alphabet)]
+    fn get_alphabet(&self) -> Vec<char> {
+        self.inner.alphabet().to_vec()
+    }
+
+    #[getter(just_prefix)]
+    fn get_just_prefix(&self) -> bool {
+        self.inner.is_just_prefix()
+    }
+
     fn is_empty(&self) -> bool {
-        self.patterns.iter().any(|patt| self.prefix.contains(patt))
+        self.inner.is_empty()
     }
 
     fn is_atom(&self) -> bool {
-        self.is_just_prefix
+        self.inner.is_atom()
     }
 
     fn removable_prefix_length(&self) -> usize {
-        let m = self.patterns.iter().map(|s| s.len()).max().unwrap_or(1);
-        let mut safe = if self.prefix.len() > m {
-            self.prefix.len() - m + 0
-        } else {
-            0
-        };
-        for i in safe..self.prefix.len() {
-            let end = &self.prefix[i..];
-            if self
-                .patterns
-                .iter()
-                .any(|patt| end == &patt[..cmp::min(end.len(), patt.len())])
-            {
-                break;
-            }
-            safe = i + 1;
-        }
-        safe
+        self.inner.removable_prefix_length()
     }
 
     fn expand_one_letter(&self) -> Vec<AvoidingWithPrefix> {
-        let mut res = Vec::with_capacity(self.alphabet.len() + 1);
-        res.push(self.with_same_base(&self.prefix, true));
-        for letter in self.alphabet.iter() {
-            let mut prefix = self.prefix.clone();
-            prefix.push(*letter);
-            res.push(self.with_same_base(&prefix, false));
-        }
-        res
+        self.inner
+            .expand_one_letter()
+            .into_iter()
+            .map(|inner| AvoidingWithPrefix { inner })
+            .collect()
     }
 
     fn remove_front_of_prefix(&self) -> Option<Vec<AvoidingWithPrefix>> {
-        if self.is_just_prefix {
-            return None;
-        }
-        match self.removable_prefix_length() {
-            0 => None,
-            safe => {
-                let start_prefix = &self.prefix[..safe];
-                let end_prefix = &self.prefix[safe..];
-                Some(vec![
-                    self.with_same_base(start_prefix, true),
-                    self.with_same_base(end_prefix, false),
-                ])
-            }
-        }
+        self.inner.remove_front_of_prefix().map(|classes| {
+            classes
+                .into_iter()
+                .map(|inner| AvoidingWithPrefix { inner })
+                .collect()
+        })
     }
 
     fn minimum_size_of_object(&self) -> usize {
-        self.prefix.len()
+        self.inner.minimum_size_of_object()
     }
 
     fn __hash__(&self) -> u64 {
@@ -199,9 +191,13 @@ impl AvoidingWithPrefix {
     }
 
     fn __str__(&self) -> String {
+        let prefix: String = self.inner.prefix().iter().collect();
         format!(
             "{}... {:?} {:?} {:?}",
-            self.prefix, self.alphabet, self.patterns, self.is_just_prefix
+            prefix,
+            self.inner.alphabet(),
+            self.get_patterns(),
+            self.inner.is_just_prefix()
         )
     }
 
@@ -210,7 +206,84 @@ impl AvoidingWithPrefix {
     }
 
     fn count_objects_of_size(&self, n: usize) -> usize {
-        self.objects_of_size(n).count()
+        self.inner.count_objects_of_size(n)
+    }
+
+    /// Same as `count_objects_of_size`, but exponentiates the automaton's transfer
+    /// matrix instead of stepping through it one letter at a time. Worth using over
+    /// `count_objects_of_size` once `n` is large relative to the number of automaton
+    /// states, since this runs in O(S^3 log n) instead of O(n * S^2).
+    fn count_objects_of_size_via_matrix_power(&self, n: usize) -> usize {
+        self.inner.count_objects_of_size_via_matrix_power(n)
+    }
+
+    /// Which letters can still extend the current prefix without immediately
+    /// completing a forbidden pattern, and whether the prefix is already blocked.
+    fn completion_mask(&self) -> (Vec<char>, bool) {
+        self.inner.completion_mask()
+    }
+
+    fn unrank(&self, n: usize, index: usize) -> Option<String> {
+        self.inner
+            .unrank(n, index)
+            .map(|letters| letters.into_iter().collect())
+    }
+
+    fn rank(&self, word: String) -> Option<usize> {
+        let letters: Vec<char> = word.chars().collect();
+        self.inner.rank(&letters)
+    }
+
+    /// The number of distinct classes the combinatorial specification search visits
+    /// before every class it reaches has already been seen.
+    fn specification_size(&self) -> usize {
+        specification::Specification::new(self.inner.clone()).num_classes()
+    }
+
+    /// One summary line per class in the derived combinatorial specification, for
+    /// inspecting the system of equations the recurrence was derived from. Ordered by
+    /// class rather than `rules()`'s `HashMap` iteration order, so the output is
+    /// stable from run to run.
+    fn specification_summary(&self) -> Vec<String> {
+        let spec = specification::Specification::new(self.inner.clone());
+        let mut entries: Vec<_> = spec.rules().iter().collect();
+        entries.sort_by_key(|(class, _)| (*class).clone());
+        entries
+            .into_iter()
+            .map(|(_, rule)| match rule {
+                specification::Rule::Empty => "Empty".to_string(),
+                specification::Rule::Atom(size) => format!("Atom(size={size})"),
+                specification::Rule::Sum(children) => format!("Sum({} children)", children.len()),
+                specification::Rule::Product(_, _) => "Product(atom, continuation)".to_string(),
+            })
+            .collect()
+    }
+
+    /// The order of the linear recurrence derived from the class's combinatorial
+    /// specification.
+    fn recurrence_order(&self) -> usize {
+        specification::Specification::new(self.inner.clone())
+            .recurrence()
+            .order()
+    }
+
+    /// Same as `count_objects_of_size`, but evaluates a linear recurrence derived from
+    /// the class's combinatorial specification instead of running the automaton's DP
+    /// for every step, once a one-time specification search has been paid for.
+    fn count_objects_of_size_via_recurrence(&self, n: usize) -> usize {
+        let recurrence = specification::Specification::new(self.inner.clone()).recurrence();
+        if recurrence.order() == 0 || n < recurrence.valid_from {
+            return self.inner.count_objects_of_size(n);
+        }
+
+        let mut terms: Vec<i128> = (0..recurrence.valid_from)
+            .map(|k| self.inner.count_objects_of_size(k) as i128)
+            .collect();
+        for k in recurrence.valid_from..=n {
+            let previous: Vec<i128> = (1..=recurrence.order()).map(|d| terms[k - d]).collect();
+            terms.push(recurrence.next(&previous));
+        }
+        terms[n] as usize
     }
 }
 
@@ -222,101 +295,33 @@ impl AvoidingWithPrefix {
         just_prefix: bool,
     ) -> AvoidingWithPrefix {
         AvoidingWithPrefix {
-            prefix,
-            patterns,
-            alphabet,
-            is_just_prefix: just_prefix,
+            inner: avoiding::AvoidingWithPrefix::new(
+                prefix.chars().collect(),
+                patterns.into_iter().map(|patt| patt.chars().collect()).collect(),
+                alphabet,
+                just_prefix,
+            ),
         }
     }
 
     /// Create a new AvoidingWithPrefix with the same patterns and alphabet
     pub fn with_same_base(&self, prefix: &str, is_just_prefix: bool) -> AvoidingWithPrefix {
         AvoidingWithPrefix {
-            prefix: String::from(prefix),
-            alphabet: self.alphabet.clone(),
-            patterns: self.patterns.clone(),
-            is_just_prefix,
+            inner: self
+                .inner
+                .with_same_base(prefix.chars().collect(), is_just_prefix),
         }
     }
 
     pub fn contains(&self, word: &str) -> bool {
-        if word.len() < self.prefix.len() {
-            return false;
-        }
-        if word[0..self.prefix.len()] != self.prefix {
-            return false;
-        }
-        if self.patterns.iter().any(|patt| word.contains(patt)) {
-            return false;
-        }
-        true
+        let letters: Vec<char> = word.chars().collect();
+        self.inner.contains(&letters)
     }
 
     pub fn objects_of_size(&self, n: usize) -> impl Iterator<Item = String> + '_ {
-        let alphabet = self.alphabet.clone();
-        let prefix = self.prefix.chars().collect();
-        WordOfSizeIterator::new(alphabet, n, prefix).filter(|w| self.contains(w))
-    }
-}
-
-/// Iterator over all the extension of of a given size of a prefix over the alphabet
-struct WordOfSizeIterator {
-    alphabet: Vec<char>,
-    prefix: Vec<char>,
-    current: Vec<usize>,
-    done: bool,
-}
-
-impl WordOfSizeIterator {
-    fn new(alphabet: Vec<char>, size: usize, prefix: Vec<char>) -> WordOfSizeIterator {
-        let done = size < prefix.len();
-        let current = if done {
-            vec![]
-        } else {
-            vec![0; size - prefix.len()]
-        };
-        WordOfSizeIterator {
-            alphabet,
-            prefix,
-            current,
-            done,
-        }
-    }
-}
-
-impl Iterator for WordOfSizeIterator {
-    type Item = String;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.done {
-            return None;
-        }
-
-        let word: String = self
-            .prefix
-            .iter()
-            .map(|c| *c)
-            .chain(self.current.iter().map(|&i| self.alphabet[i]))
-            .collect();
-
-        // increment the current word
-        if self.current.len() == 0 {
-            self.done = true
-        } else {
-            for i in (0..self.current.len()).rev() {
-                if self.current[i] < self.alphabet.len() - 1 {
-                    self.current[i] += 1;
-                    break;
-                } else {
-                    self.current[i] = 0;
-                    if i == 0 {
-                        self.done = true;
-                    }
-                }
-            }
-        }
-
-        Some(word)
+        self.inner
+            .objects_of_size(n)
+            .map(|letters| letters.into_iter().collect())
     }
 }
 
@@ -0,0 +1,347 @@
+//! Minimal univariate polynomial and rational-function arithmetic, just enough to
+//! solve the linear systems that `specification::Specification` builds and to read
+//! off a linear recurrence from the resulting rational generating function.
+
+/// A univariate polynomial with integer coefficients, `coefficients[i]` being the
+/// coefficient of `x^i`. Always trimmed so the highest-degree coefficient, if any, is
+/// non-zero; the zero polynomial is the empty vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Polynomial {
+    coefficients: Vec<i128>,
+}
+
+impl Polynomial {
+    pub(crate) fn zero() -> Polynomial {
+        Polynomial {
+            coefficients: vec![],
+        }
+    }
+
+    pub(crate) fn constant(value: i128) -> Polynomial {
+        Polynomial::monomial(0, value)
+    }
+
+    pub(crate) fn monomial(power: usize, coeff: i128) -> Polynomial {
+        if coeff == 0 {
+            return Polynomial::zero();
+        }
+        let mut coefficients = vec![0; power + 1];
+        coefficients[power] = coeff;
+        Polynomial { coefficients }
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.coefficients.is_empty()
+    }
+
+    pub(crate) fn degree(&self) -> Option<usize> {
+        if self.coefficients.is_empty() {
+            None
+        } else {
+            Some(self.coefficients.len() - 1)
+        }
+    }
+
+    pub(crate) fn coefficients(&self) -> &[i128] {
+        &self.coefficients
+    }
+
+    fn coeff(&self, power: usize) -> i128 {
+        self.coefficients.get(power).copied().unwrap_or(0)
+    }
+
+    fn leading_coeff(&self) -> i128 {
+        *self.coefficients.last().expect("leading_coeff of the zero polynomial")
+    }
+
+    fn trim(mut coefficients: Vec<i128>) -> Vec<i128> {
+        while coefficients.last() == Some(&0) {
+            coefficients.pop();
+        }
+        coefficients
+    }
+
+    fn scale(&self, factor: i128) -> Polynomial {
+        if factor == 0 {
+            return Polynomial::zero();
+        }
+        Polynomial {
+            coefficients: self.coefficients.iter().map(|c| c * factor).collect(),
+        }
+    }
+
+    /// The gcd of the (non-zero) coefficients, i.e. the largest integer that can be
+    /// factored out of every term. `0` for the zero polynomial.
+    fn content(&self) -> i128 {
+        self.coefficients.iter().fold(0i128, |acc, &c| gcd_i128(acc, c))
+    }
+
+    /// `self` divided by its content, with a sign flip if needed so the leading
+    /// coefficient is positive. The zero polynomial is its own primitive part.
+    fn primitive_part(&self) -> Polynomial {
+        if self.is_zero() {
+            return Polynomial::zero();
+        }
+        let mut content = self.content();
+        if self.leading_coeff() < 0 {
+            content = -content;
+        }
+        Polynomial {
+            coefficients: self.coefficients.iter().map(|c| c / content).collect(),
+        }
+    }
+
+    /// Pseudo-remainder of `self` divided by `divisor`: `self` reduced modulo `divisor`,
+    /// with the running remainder scaled by `lc(divisor)` before every reduction step so
+    /// that each step is an exact integer subtraction. Used to compute a polynomial gcd
+    /// without leaving the integers.
+    fn pseudo_remainder(&self, divisor: &Polynomial) -> Polynomial {
+        assert!(!divisor.is_zero(), "pseudo_remainder by the zero polynomial");
+        let divisor_degree = divisor.degree().unwrap();
+        let divisor_lc = divisor.leading_coeff();
+        let mut remainder = self.clone();
+        while let Some(degree) = remainder.degree() {
+            if degree < divisor_degree {
+                break;
+            }
+            let lc = remainder.leading_coeff();
+            let shift = degree - divisor_degree;
+            remainder = remainder
+                .scale(divisor_lc)
+                .sub(&divisor.mul(&Polynomial::monomial(shift, lc)));
+        }
+        remainder
+    }
+
+    /// `self` divided by `divisor`, assuming the division is exact (every remainder
+    /// along the way is zero). Used to divide out a gcd that is known to divide evenly.
+    fn div_exact(&self, divisor: &Polynomial) -> Polynomial {
+        assert!(!divisor.is_zero(), "div_exact by the zero polynomial");
+        if self.is_zero() {
+            return Polynomial::zero();
+        }
+        let divisor_degree = divisor.degree().unwrap();
+        let divisor_lc = divisor.leading_coeff();
+        let mut remainder = self.clone();
+        let mut quotient_coefficients = vec![0i128; self.degree().unwrap() + 1 - divisor_degree];
+        while let Some(degree) = remainder.degree() {
+            if degree < divisor_degree {
+                break;
+            }
+            let lc = remainder.leading_coeff();
+            assert!(lc % divisor_lc == 0, "divisor does not divide self exactly");
+            let factor = lc / divisor_lc;
+            let shift = degree - divisor_degree;
+            quotient_coefficients[shift] = factor;
+            remainder = remainder.sub(&divisor.mul(&Polynomial::monomial(shift, factor)));
+        }
+        assert!(remainder.is_zero(), "divisor does not divide self exactly");
+        Polynomial {
+            coefficients: Polynomial::trim(quotient_coefficients),
+        }
+    }
+
+    /// The gcd of two polynomials (up to a unit factor), computed via a pseudo-remainder
+    /// Euclidean sequence so every intermediate value stays an integer polynomial.
+    fn gcd(&self, other: &Polynomial) -> Polynomial {
+        if other.is_zero() {
+            return self.primitive_part();
+        }
+        other.gcd(&self.pseudo_remainder(other).primitive_part())
+    }
+
+    pub(crate) fn add(&self, other: &Polynomial) -> Polynomial {
+        let n = self.coefficients.len().max(other.coefficients.len());
+        let coefficients = (0..n).map(|i| self.coeff(i) + other.coeff(i)).collect();
+        Polynomial {
+            coefficients: Polynomial::trim(coefficients),
+        }
+    }
+
+    pub(crate) fn sub(&self, other: &Polynomial) -> Polynomial {
+        let n = self.coefficients.len().max(other.coefficients.len());
+        let coefficients = (0..n).map(|i| self.coeff(i) - other.coeff(i)).collect();
+        Polynomial {
+            coefficients: Polynomial::trim(coefficients),
+        }
+    }
+
+    pub(crate) fn mul(&self, other: &Polynomial) -> Polynomial {
+        if self.is_zero() || other.is_zero() {
+            return Polynomial::zero();
+        }
+        let mut coefficients = vec![0; self.coefficients.len() + other.coefficients.len() - 1];
+        for (i, &a) in self.coefficients.iter().enumerate() {
+            if a == 0 {
+                continue;
+            }
+            for (j, &b) in other.coefficients.iter().enumerate() {
+                coefficients[i + j] += a * b;
+            }
+        }
+        Polynomial {
+            coefficients: Polynomial::trim(coefficients),
+        }
+    }
+}
+
+/// A rational function `numerator / denominator` over `Polynomial`s, always kept in
+/// lowest terms (common factors divided out via `Polynomial::gcd`). Without this,
+/// repeated arithmetic in `solve_linear_system` multiplies denominators together on
+/// every step, so both the degree and the coefficients of the unreduced fraction grow
+/// exponentially and eventually overflow `i128` even for tiny inputs.
+#[derive(Clone, Debug)]
+pub(crate) struct RationalFunction {
+    pub(crate) numerator: Polynomial,
+    pub(crate) denominator: Polynomial,
+}
+
+impl RationalFunction {
+    pub(crate) fn zero() -> RationalFunction {
+        RationalFunction {
+            numerator: Polynomial::zero(),
+            denominator: Polynomial::constant(1),
+        }
+    }
+
+    pub(crate) fn from_polynomial(numerator: Polynomial) -> RationalFunction {
+        RationalFunction {
+            numerator,
+            denominator: Polynomial::constant(1),
+        }
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.numerator.is_zero()
+    }
+
+    /// `self` with every common factor of the numerator and denominator divided out,
+    /// and the sign normalized so the denominator's leading coefficient is positive.
+    fn reduced(numerator: Polynomial, denominator: Polynomial) -> RationalFunction {
+        if numerator.is_zero() {
+            return RationalFunction::zero();
+        }
+        let gcd = numerator.gcd(&denominator);
+        let mut numerator = numerator.div_exact(&gcd);
+        let mut denominator = denominator.div_exact(&gcd);
+        if !denominator.is_zero() && denominator.leading_coeff() < 0 {
+            numerator = numerator.scale(-1);
+            denominator = denominator.scale(-1);
+        }
+        RationalFunction {
+            numerator,
+            denominator,
+        }
+    }
+
+    pub(crate) fn add(&self, other: &RationalFunction) -> RationalFunction {
+        RationalFunction::reduced(
+            self.numerator
+                .mul(&other.denominator)
+                .add(&other.numerator.mul(&self.denominator)),
+            self.denominator.mul(&other.denominator),
+        )
+    }
+
+    pub(crate) fn sub(&self, other: &RationalFunction) -> RationalFunction {
+        RationalFunction::reduced(
+            self.numerator
+                .mul(&other.denominator)
+                .sub(&other.numerator.mul(&self.denominator)),
+            self.denominator.mul(&other.denominator),
+        )
+    }
+
+    pub(crate) fn mul(&self, other: &RationalFunction) -> RationalFunction {
+        RationalFunction::reduced(
+            self.numerator.mul(&other.numerator),
+            self.denominator.mul(&other.denominator),
+        )
+    }
+
+    pub(crate) fn div(&self, other: &RationalFunction) -> RationalFunction {
+        RationalFunction::reduced(
+            self.numerator.mul(&other.denominator),
+            self.denominator.mul(&other.numerator),
+        )
+    }
+}
+
+fn gcd_i128(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Solve `matrix * x = rhs` for `x` by Gauss-Jordan elimination over the field of
+/// rational functions in `x`. To solve `(I - M) * x = rhs`, pass `I - M` as `matrix`,
+/// not `M`.
+pub(crate) fn solve_linear_system(
+    mut matrix: Vec<Vec<RationalFunction>>,
+    mut rhs: Vec<RationalFunction>,
+) -> Vec<RationalFunction> {
+    let n = rhs.len();
+    for col in 0..n {
+        let pivot_row = (col..n)
+            .find(|&row| !matrix[row][col].is_zero())
+            .expect("system of equations from a Specification is never singular");
+        matrix.swap(col, pivot_row);
+        rhs.swap(col, pivot_row);
+
+        let pivot = matrix[col][col].clone();
+        for entry in matrix[col].iter_mut().skip(col) {
+            *entry = entry.div(&pivot);
+        }
+        rhs[col] = rhs[col].div(&pivot);
+
+        let pivot_row_values = matrix[col][col..].to_vec();
+        for row in 0..n {
+            if row == col || matrix[row][col].is_zero() {
+                continue;
+            }
+            let factor = matrix[row][col].clone();
+            for (offset, pivot_value) in pivot_row_values.iter().enumerate() {
+                let j = col + offset;
+                let delta = pivot_value.mul(&factor);
+                matrix[row][j] = matrix[row][j].sub(&delta);
+            }
+            let delta = rhs[col].mul(&factor);
+            rhs[row] = rhs[row].sub(&delta);
+        }
+    }
+    rhs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Polynomial, RationalFunction};
+
+    #[test]
+    fn mul_then_div_by_the_same_factor_reduces_back_to_the_original() {
+        // (1 + x) / 1, multiplied and then divided by (1 - x), should reduce back to
+        // its original lowest terms instead of carrying the common (1 - x) factor.
+        let one_plus_x = RationalFunction::from_polynomial(Polynomial::constant(1).add(&Polynomial::monomial(1, 1)));
+        let one_minus_x = RationalFunction::from_polynomial(Polynomial::constant(1).sub(&Polynomial::monomial(1, 1)));
+        let product = one_plus_x.mul(&one_minus_x);
+        let back = product.div(&one_minus_x);
+        assert_eq!(back.numerator.coefficients(), one_plus_x.numerator.coefficients());
+        assert_eq!(back.denominator.coefficients(), one_plus_x.denominator.coefficients());
+    }
+
+    #[test]
+    fn repeated_addition_does_not_grow_denominator_degree_unboundedly() {
+        // Each `add` of two degree-1-denominator fractions would double the
+        // denominator's degree if never reduced; after reduction it should stay small.
+        let mut sum = RationalFunction::zero();
+        let term = RationalFunction {
+            numerator: Polynomial::constant(1),
+            denominator: Polynomial::constant(1).sub(&Polynomial::monomial(1, 1)),
+        };
+        for _ in 0..10 {
+            sum = sum.add(&term);
+        }
+        assert!(sum.denominator.degree().unwrap_or(0) <= 1);
+    }
+}
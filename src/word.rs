@@ -0,0 +1,96 @@
+use std::hash::Hash;
+
+/// A word over an alphabet of letters `L`. Generic over the letter type the same way a
+/// generalized trie is generic over its symbol type, so words aren't limited to
+/// sequences of `char` and can range over integer alphabets, tuples, or anything else.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Word<L: Eq + Hash + Clone> {
+    pub letters: Vec<L>,
+}
+
+impl<L: Eq + Hash + Clone> Word<L> {
+    pub fn new(letters: Vec<L>) -> Word<L> {
+        Word { letters }
+    }
+
+    pub fn len(&self) -> usize {
+        self.letters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.letters.is_empty()
+    }
+
+    pub fn contains(&self, pattern: &Word<L>) -> bool {
+        if pattern.letters.is_empty() {
+            return true;
+        }
+        if pattern.letters.len() > self.letters.len() {
+            return false;
+        }
+        self.letters
+            .windows(pattern.letters.len())
+            .any(|w| w == pattern.letters.as_slice())
+    }
+}
+
+/// Iterator over all the extensions of a given size of a prefix over the alphabet.
+pub struct WordOfSizeIterator<L: Clone> {
+    alphabet: Vec<L>,
+    prefix: Vec<L>,
+    current: Vec<usize>,
+    done: bool,
+}
+
+impl<L: Clone> WordOfSizeIterator<L> {
+    pub fn new(alphabet: Vec<L>, size: usize, prefix: Vec<L>) -> WordOfSizeIterator<L> {
+        let done = size < prefix.len();
+        let current = if done {
+            vec![]
+        } else {
+            vec![0; size - prefix.len()]
+        };
+        WordOfSizeIterator {
+            alphabet,
+            prefix,
+            current,
+            done,
+        }
+    }
+}
+
+impl<L: Clone> Iterator for WordOfSizeIterator<L> {
+    type Item = Vec<L>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let word: Vec<L> = self
+            .prefix
+            .iter()
+            .cloned()
+            .chain(self.current.iter().map(|&i| self.alphabet[i].clone()))
+            .collect();
+
+        // increment the current word
+        if self.current.is_empty() {
+            self.done = true
+        } else {
+            for i in (0..self.current.len()).rev() {
+                if self.current[i] < self.alphabet.len() - 1 {
+                    self.current[i] += 1;
+                    break;
+                } else {
+                    self.current[i] = 0;
+                    if i == 0 {
+                        self.done = true;
+                    }
+                }
+            }
+        }
+
+        Some(word)
+    }
+}